@@ -0,0 +1,301 @@
+use clap::ValueEnum;
+use cpal::{FromSample, Sample};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::Opt;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Wav,
+    /// Ogg Opus at 48 kHz; needs the `opus` feature
+    Opus,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Wav => "wav",
+            ArchiveFormat::Opus => "opus",
+        }
+    }
+}
+
+/// Local rolling copy of every recorded chunk, kept independently of uploads.
+pub struct Archive {
+    dir: PathBuf,
+    formats: Vec<ArchiveFormat>,
+    retention: Duration,
+    /// Whether chunks recorded off the record are archived too
+    private: bool,
+}
+
+impl Archive {
+    pub fn new(opt: &Opt) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Some(dir) = &opt.archive_dir else {
+            return Ok(None);
+        };
+        if cfg!(not(feature = "opus")) && opt.archive_format.contains(&ArchiveFormat::Opus) {
+            return Err("opus archives need the client built with --features opus".into());
+        }
+        std::fs::create_dir_all(dir)?;
+
+        Ok(Some(Archive {
+            dir: dir.clone(),
+            formats: opt.archive_format.clone(),
+            retention: Duration::from_secs(opt.archive_retention_hours * 3600),
+            private: opt.archive_private,
+        }))
+    }
+
+    /// Archives the chunk just written to `wav` in every configured format,
+    /// under the chunk's own name, and drops archived chunks older than the
+    /// retention period. Chunks recorded off the record are skipped unless
+    /// `--archive-private` asks for them.
+    pub fn store<T: Sample>(
+        &self,
+        wav: &Path,
+        samples: &[T],
+        spec: hound::WavSpec,
+        private: bool,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        f32: FromSample<T>,
+    {
+        if private && !self.private {
+            return Ok(());
+        }
+        let name = wav.file_stem().ok_or("chunk without a file name")?;
+        for format in &self.formats {
+            let path = self.dir.join(name).with_extension(format.extension());
+            match format {
+                ArchiveFormat::Wav => {
+                    std::fs::copy(wav, &path)?;
+                }
+                ArchiveFormat::Opus => {
+                    let samples: Vec<f32> = samples.iter().map(|s| s.to_sample()).collect();
+                    write_opus(&path, &samples, spec)?;
+                }
+            }
+        }
+
+        self.prune()
+    }
+
+    fn prune(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(cutoff) = SystemTime::now().checked_sub(self.retention) else {
+            return Ok(());
+        };
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let archived = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("audio_"))
+                && path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| self.formats.iter().any(|f| f.extension() == e));
+            if archived && entry.metadata()?.modified()? < cutoff {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "opus"))]
+fn write_opus(
+    _path: &Path,
+    _samples: &[f32],
+    _spec: hound::WavSpec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("opus archives need the client built with --features opus".into())
+}
+
+/// Encodes interleaved samples as an Ogg Opus file. Opus only runs at a few
+/// fixed rates, so the audio is resampled to 48 kHz and cut down to stereo.
+#[cfg(feature = "opus")]
+fn write_opus(
+    path: &Path,
+    samples: &[f32],
+    spec: hound::WavSpec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    const RATE: u32 = 48000;
+    const FRAME: usize = 960; // 20 ms at 48 kHz
+    const SERIAL: u32 = 0x6574_7473;
+
+    let in_channels = spec.channels.max(1) as usize;
+    let channels = in_channels.min(2);
+    let pcm = resample(samples, in_channels, channels, spec.sample_rate, RATE);
+
+    let mut encoder = opus::Encoder::new(
+        RATE,
+        if channels == 1 {
+            opus::Channels::Mono
+        } else {
+            opus::Channels::Stereo
+        },
+        opus::Application::Voip,
+    )?;
+    let pre_skip = encoder.get_lookahead()? as u16;
+
+    let mut writer = PacketWriter::new(std::io::BufWriter::new(std::fs::File::create(path)?));
+
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    writer.write_packet(head.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+    let vendor = opus::version();
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    writer.write_packet(tags.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+    let frames: Vec<&[f32]> = pcm.chunks(FRAME * channels).collect();
+    let mut packet = [0u8; 4000];
+    let mut granule = pre_skip as u64;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut padded = frame.to_vec();
+        padded.resize(FRAME * channels, 0.0);
+        let len = encoder.encode_float(&padded, &mut packet)?;
+        granule += (frame.len() / channels) as u64;
+        let end = if i + 1 == frames.len() {
+            PacketWriteEndInfo::EndStream
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        writer.write_packet(packet[..len].into(), SERIAL, end, granule)?;
+    }
+
+    Ok(())
+}
+
+/// Linearly resamples interleaved audio, keeping the first `out_channels`.
+#[cfg(feature = "opus")]
+fn resample(
+    samples: &[f32],
+    in_channels: usize,
+    out_channels: usize,
+    from: u32,
+    to: u32,
+) -> Vec<f32> {
+    let frames = samples.len() / in_channels;
+    if frames == 0 {
+        return Vec::new();
+    }
+    let out_frames = (frames as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    let mut out = Vec::with_capacity(out_frames * out_channels);
+    for n in 0..out_frames {
+        let pos = n as f64 * step;
+        let i = (pos as usize).min(frames - 1);
+        let j = (i + 1).min(frames - 1);
+        let frac = (pos - i as f64) as f32;
+        for c in 0..out_channels {
+            let a = samples[i * in_channels + c];
+            let b = samples[j * in_channels + c];
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(name: &str, formats: Vec<ArchiveFormat>) -> Archive {
+        let dir = std::env::temp_dir().join(format!("archive-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Archive {
+            dir,
+            formats,
+            retention: Duration::from_secs(3600),
+            private: false,
+        }
+    }
+
+    fn touch(path: &Path, age: Duration) {
+        let file = std::fs::File::create(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn prune_drops_only_expired_chunks() {
+        let archive = archive("prune", vec![ArchiveFormat::Wav]);
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        let expired = archive.dir.join("audio_1.wav");
+        let recent = archive.dir.join("audio_2.wav");
+        let unrelated = archive.dir.join("notes.wav");
+        let other_format = archive.dir.join("audio_3.opus");
+        touch(&expired, hours(2));
+        touch(&recent, Duration::ZERO);
+        touch(&unrelated, hours(2));
+        touch(&other_format, hours(2));
+
+        archive.prune().unwrap();
+        assert!(!expired.exists());
+        assert!(recent.exists());
+        assert!(unrelated.exists());
+        assert!(other_format.exists());
+        std::fs::remove_dir_all(&archive.dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_keep_their_names() {
+        let archive = archive("store", vec![ArchiveFormat::Wav]);
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let wav = archive.dir.join("chunk.tmp");
+        std::fs::write(&wav, b"RIFF").unwrap();
+
+        archive.store(&wav, &[0i16; 4], spec, false).unwrap();
+        assert_eq!(
+            std::fs::read(archive.dir.join("chunk.wav")).unwrap(),
+            b"RIFF"
+        );
+
+        std::fs::remove_file(archive.dir.join("chunk.wav")).unwrap();
+        archive.store(&wav, &[0i16; 4], spec, true).unwrap();
+        assert!(
+            !archive.dir.join("chunk.wav").exists(),
+            "private chunk archived"
+        );
+        std::fs::remove_dir_all(&archive.dir).unwrap();
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn resample_keeps_rate_and_channels() {
+        let samples = [0.1, -0.1, 0.2, -0.2, 0.3, -0.3];
+        assert_eq!(resample(&samples, 2, 2, 48000, 48000), samples);
+        assert_eq!(resample(&samples, 2, 1, 48000, 48000), [0.1, 0.2, 0.3]);
+        assert!(resample(&[], 2, 2, 44100, 48000).is_empty());
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn resample_interpolates() {
+        let up = resample(&[0.0, 1.0], 1, 1, 24000, 48000);
+        assert_eq!(up, [0.0, 0.5, 1.0, 1.0]);
+        let down = resample(&[0.0, 0.25, 0.5, 0.75], 1, 1, 48000, 24000);
+        assert_eq!(down, [0.0, 0.5]);
+        let mono = resample(&[1.0, 0.0, 1.0], 3, 1, 44100, 48000);
+        assert_eq!(mono.len(), 1);
+    }
+}
@@ -0,0 +1,373 @@
+use eframe::egui;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, warn};
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{MouseButton, TrayIcon, TrayIconBuilder, TrayIconEvent};
+
+use crate::session::Controls;
+use crate::upload::Target;
+use crate::Opt;
+
+const SUMMARY_REFRESH: Duration = Duration::from_secs(60);
+const ICON_SIZE: u32 = 32;
+
+/// Opens the control window and tray icon, and records in the background
+/// until the window is quit.
+pub fn run(
+    runtime: tokio::runtime::Runtime,
+    opt: Opt,
+    controls: Arc<Controls>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = opt.targets[0].clone();
+    let keys: Vec<String> = opt
+        .targets
+        .iter()
+        .filter_map(|t| t.system_key().map(String::from))
+        .collect();
+
+    let recorder = controls.clone();
+    std::thread::spawn(move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            runtime.block_on(crate::record(opt, recorder.clone()))
+        }));
+        // Recording runs for as long as the process does, so any way out of
+        // it is a failure the user has to see.
+        let reason = match result {
+            Ok(Ok(())) => String::from("audio capture stopped"),
+            Ok(Err(e)) => e.to_string(),
+            Err(panic) => panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("recorder crashed")),
+        };
+        error!(%reason, "recording failed");
+        recorder.set_failed(reason);
+    });
+
+    // On Linux the tray lives on a GTK main loop of its own; elsewhere it has
+    // to be created on the window's thread.
+    #[cfg(target_os = "linux")]
+    linux_tray_thread(controls.clone());
+
+    let app = App {
+        controls,
+        key: keys.first().cloned().unwrap_or_default(),
+        keys,
+        target,
+        summary: Arc::new(Mutex::new(String::from("No summary yet"))),
+        quit: Arc::new(AtomicBool::new(false)),
+        #[cfg(not(target_os = "linux"))]
+        tray: None,
+    };
+    app.summary_thread();
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([380.0, 460.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Recorder",
+        options,
+        Box::new(|cc| {
+            #[allow(unused_mut)]
+            let mut app = app;
+            app.handle_tray_events(&cc.egui_ctx);
+            #[cfg(not(target_os = "linux"))]
+            {
+                app.tray = Tray::new()
+                    .map_err(|e| warn!(error = %e, "tray icon unavailable"))
+                    .ok();
+            }
+            Ok(Box::new(app))
+        }),
+    )?;
+    Ok(())
+}
+
+/// What the heading and the tray icon report.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Recording,
+    Paused,
+    Stopped,
+    Failed,
+}
+
+impl State {
+    fn of(controls: &Controls) -> Self {
+        if controls.failed().is_some() {
+            State::Failed
+        } else if controls.stopped() {
+            State::Stopped
+        } else if controls.paused() {
+            State::Paused
+        } else {
+            State::Recording
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            State::Recording => "Recording",
+            State::Paused => "Paused",
+            State::Stopped => "Stopped",
+            State::Failed => "Not recording",
+        }
+    }
+
+    fn colour(self) -> [u8; 3] {
+        match self {
+            State::Recording => [0xd0, 0x20, 0x20],
+            State::Paused => [0xe0, 0xa0, 0x20],
+            State::Stopped => [0x80, 0x80, 0x80],
+            State::Failed => [0x20, 0x20, 0x20],
+        }
+    }
+
+    /// A filled dot in the state's colour.
+    fn icon(self) -> Result<tray_icon::Icon, tray_icon::BadIcon> {
+        let [r, g, b] = self.colour();
+        let centre = (ICON_SIZE as f32 - 1.0) / 2.0;
+        let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+        for y in 0..ICON_SIZE {
+            for x in 0..ICON_SIZE {
+                let distance = (x as f32 - centre).hypot(y as f32 - centre);
+                let alpha = if distance <= centre - 2.0 { 0xff } else { 0 };
+                rgba.extend_from_slice(&[r, g, b, alpha]);
+            }
+        }
+        tray_icon::Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)
+    }
+}
+
+/// Tray icon with a menu mirroring the window's controls.
+struct Tray {
+    icon: TrayIcon,
+    shown: Option<State>,
+}
+
+impl Tray {
+    fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let menu = Menu::new();
+        menu.append_items(&[
+            &MenuItem::with_id("start", "Start", true, None),
+            &MenuItem::with_id("stop", "Stop", true, None),
+            &MenuItem::with_id("pause", "Pause / resume", true, None),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id("show", "Show window", true, None),
+            &MenuItem::with_id("quit", "Quit", true, None),
+        ])?;
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_icon(State::Stopped.icon()?)
+            .build()?;
+        Ok(Tray { icon, shown: None })
+    }
+
+    fn show(&mut self, state: State) {
+        if self.shown == Some(state) {
+            return;
+        }
+        if let Err(e) = state.icon().map(|i| self.icon.set_icon(Some(i))) {
+            warn!(error = %e, "failed to update tray icon");
+        }
+        let _ = self.icon.set_tooltip(Some(state.label()));
+        self.shown = Some(state);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn linux_tray_thread(controls: Arc<Controls>) {
+    std::thread::spawn(move || {
+        if let Err(e) = gtk::init() {
+            warn!(error = %e, "tray icon unavailable");
+            return;
+        }
+        let mut tray = match Tray::new() {
+            Ok(tray) => tray,
+            Err(e) => {
+                warn!(error = %e, "tray icon unavailable");
+                return;
+            }
+        };
+        gtk::glib::timeout_add_local(Duration::from_secs(1), move || {
+            tray.show(State::of(&controls));
+            gtk::glib::ControlFlow::Continue
+        });
+        gtk::main();
+    });
+}
+
+struct App {
+    controls: Arc<Controls>,
+    keys: Vec<String>,
+    key: String,
+    target: Target,
+    summary: Arc<Mutex<String>>,
+    // Set by the tray's Quit; closing the window otherwise only minimizes it.
+    quit: Arc<AtomicBool>,
+    #[cfg(not(target_os = "linux"))]
+    tray: Option<Tray>,
+}
+
+impl App {
+    /// Keeps the summary preview current for whichever key is picked.
+    fn summary_thread(&self) {
+        let controls = self.controls.clone();
+        let summary = self.summary.clone();
+        let target = self.target.clone();
+        let fallback = self.key.clone();
+        std::thread::spawn(move || loop {
+            let key = controls.system_key().unwrap_or_else(|| fallback.clone());
+            if !key.is_empty() {
+                *summary.lock().unwrap() = fetch_summary(&target, &key);
+            }
+            std::thread::sleep(SUMMARY_REFRESH);
+        });
+    }
+
+    fn refresh_summary(&self) {
+        let summary = self.summary.clone();
+        let target = self.target.clone();
+        let key = self.key.clone();
+        std::thread::spawn(move || *summary.lock().unwrap() = fetch_summary(&target, &key));
+    }
+
+    /// Acts on the tray menu and icon clicks, whichever thread they arrive on.
+    fn handle_tray_events(&self, ctx: &egui::Context) {
+        let menu_ctx = ctx.clone();
+        let controls = self.controls.clone();
+        let quit = self.quit.clone();
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            match event.id.as_ref() {
+                "start" => {
+                    controls.set_paused(false);
+                    controls.set_stopped(false);
+                }
+                "stop" => controls.set_stopped(true),
+                "pause" => controls.set_paused(!controls.paused()),
+                "show" => show_window(&menu_ctx),
+                "quit" => {
+                    quit.store(true, Ordering::SeqCst);
+                    menu_ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                _ => {}
+            }
+            menu_ctx.request_repaint();
+        }));
+
+        let click_ctx = ctx.clone();
+        TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                ..
+            } = event
+            {
+                show_window(&click_ctx);
+            }
+        }));
+    }
+}
+
+fn show_window(ctx: &egui::Context) {
+    ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    ctx.request_repaint();
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Minimize to the tray instead of quitting; Quit in the tray menu exits.
+        if ctx.input(|i| i.viewport().close_requested()) && !self.quit.load(Ordering::SeqCst) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+        }
+
+        let state = State::of(&self.controls);
+        #[cfg(not(target_os = "linux"))]
+        if let Some(tray) = &mut self.tray {
+            tray.show(state);
+        }
+        let stopped = self.controls.stopped();
+        let paused = self.controls.paused();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(state.label());
+            if let Some(reason) = self.controls.failed() {
+                ui.colored_label(ui.visuals().error_fg_color, reason);
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(stopped, egui::Button::new("Start"))
+                    .clicked()
+                {
+                    self.controls.set_paused(false);
+                    self.controls.set_stopped(false);
+                }
+                if ui
+                    .add_enabled(!stopped, egui::Button::new("Stop"))
+                    .clicked()
+                {
+                    self.controls.set_stopped(true);
+                }
+                let label = if paused { "Resume" } else { "Pause" };
+                if ui.add_enabled(!stopped, egui::Button::new(label)).clicked() {
+                    self.controls.set_paused(!paused);
+                }
+            });
+
+            ui.separator();
+            ui.label("System key");
+            let before = self.key.clone();
+            egui::ComboBox::from_id_salt("system_key")
+                .selected_text(self.key.as_str())
+                .show_ui(ui, |ui| {
+                    for key in &self.keys {
+                        ui.selectable_value(&mut self.key, key.clone(), key.as_str());
+                    }
+                });
+            ui.text_edit_singleline(&mut self.key);
+            if self.key != before {
+                let key = self.key.trim();
+                self.controls
+                    .set_system_key((!key.is_empty()).then(|| key.to_string()));
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Last summary");
+                if ui.button("Refresh").clicked() && !self.key.is_empty() {
+                    self.refresh_summary();
+                }
+            });
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.label(self.summary.lock().unwrap().as_str());
+            });
+        });
+
+        // Pick up state changes made off the UI thread.
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+}
+
+/// Fetches the latest summary for `key`, or a line saying why there is none.
+fn fetch_summary(target: &Target, key: &str) -> String {
+    let response = reqwest::blocking::get(target.summary_url(key))
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text());
+    match response {
+        Ok(body) => match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(json) => json
+                .get("summary")
+                .and_then(|s| s.as_str())
+                .map(String::from)
+                .unwrap_or(body),
+            Err(_) => body,
+        },
+        Err(e) => format!("Could not load summary: {}", e),
+    }
+}
@@ -0,0 +1,234 @@
+use clap::Args;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::upload::Uploader;
+
+const SYNTHETIC_SAMPLE_RATE: u32 = 16000;
+
+/// A named WAV payload to upload.
+type Payload = (String, Vec<u8>);
+
+#[derive(Args, Debug)]
+pub struct LoadtestArgs {
+    /// Directory of WAV fixtures to replay; synthetic audio is generated when omitted
+    #[arg(long)]
+    fixtures: Option<PathBuf>,
+
+    /// Uploads started per second
+    #[arg(long, default_value_t = 1.0)]
+    rate: f64,
+
+    /// Total number of uploads to send
+    #[arg(long, default_value_t = 60)]
+    requests: usize,
+
+    /// Length of each synthetic chunk in seconds
+    #[arg(long, default_value_t = 30)]
+    chunk_seconds: u32,
+}
+
+impl LoadtestArgs {
+    /// Time between upload starts, once the arguments make sense.
+    fn period(&self) -> Result<Duration, String> {
+        if self.requests == 0 {
+            return Err("--requests must be at least 1".to_string());
+        }
+        // NaN, infinite, negative and zero rates fail here, and so do rates
+        // too high for the timer to tell apart.
+        match Duration::try_from_secs_f64(1.0 / self.rate) {
+            Ok(period) if !period.is_zero() => Ok(period),
+            _ => Err(format!(
+                "--rate must be a positive number of uploads per second, at most 1e9; got {}",
+                self.rate
+            )),
+        }
+    }
+}
+
+/// Replays fixtures (or generated audio) against the server at a fixed rate and
+/// prints latency percentiles and the error rate. Point it at a server running
+/// with its mock LLM so the numbers measure the pipeline, not the provider.
+pub async fn run(
+    uploader: &Uploader,
+    args: &LoadtestArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let period = args.period()?;
+
+    let payloads = match &args.fixtures {
+        Some(dir) => load_fixtures(dir)?,
+        None => vec![(
+            "synthetic.wav".to_string(),
+            synthetic_wav(args.chunk_seconds)?,
+        )],
+    };
+    println!(
+        "Sending {} uploads at {}/s from {} payload(s)",
+        args.requests,
+        args.rate,
+        payloads.len()
+    );
+
+    let mut ticker = tokio::time::interval(period);
+    let mut tasks = Vec::with_capacity(args.requests);
+    for i in 0..args.requests {
+        ticker.tick().await;
+        let (name, data) = payloads[i % payloads.len()].clone();
+        let uploader = uploader.clone();
+        tasks.push(tokio::spawn(async move {
+            let started = Instant::now();
+            let result = match uploader.upload(&name, data, false).await {
+                Ok(status) if status.is_success() => Ok(()),
+                Ok(status) => Err(status.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            (started.elapsed(), result)
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(tasks.len());
+    let mut errors = 0;
+    for task in tasks {
+        let (latency, result) = task.await?;
+        match result {
+            Ok(()) => latencies.push(latency),
+            Err(e) => {
+                errors += 1;
+                warn!(error = %e, "upload failed");
+            }
+        }
+    }
+    latencies.sort();
+
+    println!(
+        "Completed {} uploads, {} failed ({:.1}% error rate)",
+        args.requests,
+        errors,
+        errors as f64 * 100.0 / args.requests as f64
+    );
+    if !latencies.is_empty() {
+        println!(
+            "Latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            percentile(&latencies, 50.0),
+            percentile(&latencies, 90.0),
+            percentile(&latencies, 99.0),
+            latencies[latencies.len() - 1]
+        );
+    }
+
+    Ok(())
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn load_fixtures(dir: &Path) -> Result<Vec<Payload>, Box<dyn std::error::Error>> {
+    let mut fixtures = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            fixtures.push((name, std::fs::read(&path)?));
+        }
+    }
+    if fixtures.is_empty() {
+        return Err(format!("no .wav fixtures in {}", dir.display()).into());
+    }
+    fixtures.sort();
+    Ok(fixtures)
+}
+
+/// Generates a mono WAV of a warbling tone over low-level noise, which is close
+/// enough to speech to get past silence detection.
+fn synthetic_wav(seconds: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SYNTHETIC_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    let mut noise: u32 = 0x2545_f491;
+    for n in 0..seconds * SYNTHETIC_SAMPLE_RATE {
+        let t = n as f32 / SYNTHETIC_SAMPLE_RATE as f32;
+        let pitch = 180.0 + 40.0 * (t * 3.0 * std::f32::consts::TAU).sin();
+        let tone = (t * pitch * std::f32::consts::TAU).sin() * 0.3;
+        noise ^= noise << 13;
+        noise ^= noise >> 17;
+        noise ^= noise << 5;
+        let hiss = (noise as f32 / u32::MAX as f32 - 0.5) * 0.02;
+        writer.write_sample(((tone + hiss) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&v| Duration::from_millis(v)).collect()
+    }
+
+    fn args(rate: f64, requests: usize) -> LoadtestArgs {
+        LoadtestArgs {
+            fixtures: None,
+            rate,
+            requests,
+            chunk_seconds: 1,
+        }
+    }
+
+    #[test]
+    fn period_follows_rate() {
+        assert_eq!(args(1.0, 1).period(), Ok(Duration::from_secs(1)));
+        assert_eq!(args(4.0, 1).period(), Ok(Duration::from_millis(250)));
+        assert_eq!(args(0.5, 1).period(), Ok(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn unusable_rates_are_rejected() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e12] {
+            assert!(args(rate, 1).period().is_err(), "rate {}", rate);
+        }
+    }
+
+    #[test]
+    fn zero_requests_are_rejected() {
+        assert!(args(1.0, 0).period().is_err());
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let sorted = millis(&(1..=100).collect::<Vec<_>>());
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 90.0), Duration::from_millis(90));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn percentiles_of_few_samples() {
+        let one = millis(&[7]);
+        assert_eq!(percentile(&one, 0.0), Duration::from_millis(7));
+        assert_eq!(percentile(&one, 99.0), Duration::from_millis(7));
+
+        let three = millis(&[1, 2, 3]);
+        assert_eq!(percentile(&three, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&three, 50.0), Duration::from_millis(2));
+        assert_eq!(percentile(&three, 99.0), Duration::from_millis(3));
+    }
+}
@@ -0,0 +1,77 @@
+use clap::ValueEnum;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+use crate::Opt;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
+
+/// Sets up logging to stderr and, with `--log-file`, to a rotating file.
+/// The level comes from `RUST_LOG` and defaults to `info`. The returned guard
+/// flushes the file writer and must be held until exit.
+pub fn init(opt: &Opt) -> Result<Option<WorkerGuard>, Box<dyn std::error::Error>> {
+    let mut layers = vec![layer(opt.log_format, std::io::stderr, true)];
+
+    let guard = match &opt.log_file {
+        Some(path) => {
+            let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+            let prefix = path
+                .file_name()
+                .ok_or("--log-file must name a file")?
+                .to_string_lossy()
+                .into_owned();
+            let appender = RollingFileAppender::builder()
+                .rotation(opt.log_rotation.into())
+                .filename_prefix(prefix)
+                .max_log_files(opt.log_max_files)
+                .build(dir.unwrap_or_else(|| std::path::Path::new(".")))?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            layers.push(layer(opt.log_format, writer, false));
+            Some(guard)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .try_init()?;
+
+    Ok(guard)
+}
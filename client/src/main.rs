@@ -1,15 +1,40 @@
-use clap::Parser;
+mod archive;
+#[cfg(feature = "gui")]
+mod gui;
+mod loadtest;
+mod logging;
+mod private;
+mod session;
+mod spool;
+mod status;
+mod update;
+mod upload;
+
+use archive::{Archive, ArchiveFormat};
+use clap::{Parser, Subcommand};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, Sample};
+use logging::{LogFormat, LogRotation};
+use private::OffTheRecord;
+use session::{Controls, MeetingWatch};
+use spool::{Spool, UploadWindow};
+use status::Stats;
 use std::fs::File;
 use std::io::BufWriter;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use tokio::time::Instant;
+use tracing::{error, info, warn};
+use update::{UpdateChecker, UpdateMode};
+use upload::{Compression, RateLimit, Target, Uploader};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "CPAL record from device", long_about = None)]
 struct Opt {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The audio device to use
     #[arg(short, long, default_value_t = String::from("default"))]
     device: String,
@@ -27,11 +52,128 @@ struct Opt {
     #[arg(short, long)]
     #[allow(dead_code)]
     jack: bool,
+
+    /// Show a window with recording controls instead of running headless
+    #[cfg(feature = "gui")]
+    #[arg(long)]
+    gui: bool,
+
+    /// Server to upload to, as URL or URL#SYSTEM_KEY; repeat to send every
+    /// chunk to several targets
+    #[arg(
+        long = "target",
+        alias = "server-url",
+        default_value = "http://your-server-endpoint"
+    )]
+    targets: Vec<Target>,
+
+    /// Name this recorder reports with every upload so the server can tell
+    /// recorders apart; defaults to the host name
+    #[arg(long)]
+    client_id: Option<String>,
+
+    /// Compress upload bodies and send them with a matching Content-Encoding
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+
+    /// Upload files larger than this many bytes through the resumable endpoint
+    #[arg(long, default_value_t = 8 * 1024 * 1024)]
+    resumable_threshold: u64,
+
+    /// File with a hex-encoded 256-bit key shared with the server; audio is
+    /// encrypted with it before upload
+    #[arg(long)]
+    encryption_key: Option<PathBuf>,
+
+    /// Only record while this app holds a microphone, matched against its
+    /// process or application name; repeat for several, e.g. zoom and teams.
+    /// Each meeting ends with a final, possibly short, chunk
+    #[arg(long)]
+    meeting_app: Vec<String>,
+
+    /// Start off the record; type `p` and Enter to toggle while recording
+    #[arg(long)]
+    private: bool,
+
+    /// Keep a local copy of every recorded chunk in this directory
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+
+    /// Archive formats; repeat the flag to write several
+    #[arg(long, value_enum, default_values_t = [ArchiveFormat::Wav])]
+    archive_format: Vec<ArchiveFormat>,
+
+    /// Delete archived chunks older than this many hours
+    #[arg(long, default_value_t = 24 * 7)]
+    archive_retention_hours: u64,
+
+    /// Archive chunks recorded off the record too; they are left out otherwise
+    #[arg(long)]
+    archive_private: bool,
+
+    /// Directory where recorded chunks wait until they are uploaded
+    #[arg(long, default_value = "spool")]
+    spool_dir: PathBuf,
+
+    /// Limit upload bandwidth to this many kilobits per second
+    #[arg(long)]
+    max_upload_kbps: Option<u64>,
+
+    /// Only upload during this local time window, e.g. 18:00-08:00; chunks
+    /// recorded outside it are spooled until it opens
+    #[arg(long)]
+    upload_window: Option<UploadWindow>,
+
+    /// Serve a JSON status report at http://ADDR/status, e.g. 127.0.0.1:9876
+    #[arg(long)]
+    status_addr: Option<SocketAddr>,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics; may equal --status-addr
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Log line format
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Also write logs to this file, rotated as set by --log-rotation
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// How often to start a new log file
+    #[arg(long, value_enum, default_value_t = LogRotation::Daily)]
+    log_rotation: LogRotation,
+
+    /// Number of rotated log files to keep
+    #[arg(long, default_value_t = 7)]
+    log_max_files: usize,
+
+    /// URL of a JSON manifest ({"version", "url", "signature"}) describing
+    /// the latest client release
+    #[arg(long)]
+    update_url: Option<String>,
+
+    /// What to do when --update-url reports a newer version
+    #[arg(long, value_enum, default_value_t = UpdateMode::Notify)]
+    update_mode: UpdateMode,
+
+    /// Hex-encoded Ed25519 public key that releases are signed with; each
+    /// signature covers the version, a newline and the binary
+    #[arg(long)]
+    update_public_key: Option<String>,
+
+    /// Hours between update checks
+    #[arg(long, default_value_t = 24)]
+    update_interval_hours: u64,
 }
 
-fn device() -> Result<cpal::Device, Box<dyn std::error::Error>> {
-    let opt = Opt::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send generated or recorded WAV chunks to the server and report latency
+    Loadtest(loadtest::LoadtestArgs),
+}
 
+fn device(opt: &Opt) -> Result<cpal::Device, Box<dyn std::error::Error>> {
     // Conditionally compile with jack if the feature is specified.
     #[cfg(all(
         any(
@@ -78,25 +220,37 @@ fn device() -> Result<cpal::Device, Box<dyn std::error::Error>> {
     Ok(device)
 }
 
-fn capture_audio<
-    T: cpal::Sample + cpal::SizedSample + hound::Sample + std::marker::Send + 'static,
->(
+/// Sample type the chunks are buffered and written in. Device formats are
+/// converted on capture: up to 16-bit integers become `i16`, everything else
+/// (wider integers and floats) becomes `f32`.
+fn canonical_format(format: cpal::SampleFormat) -> cpal::SampleFormat {
+    if !format.is_float() && format.sample_size() <= 2 {
+        cpal::SampleFormat::I16
+    } else {
+        cpal::SampleFormat::F32
+    }
+}
+
+fn capture_audio<T, U>(
     d: cpal::Device,
     cfg: cpal::StreamConfig,
-    tx: mpsc::Sender<T>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    tx: mpsc::Sender<U>,
+    stats: Arc<Stats>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    T: cpal::SizedSample,
+    U: cpal::Sample + hound::Sample + FromSample<T> + std::marker::Send + 'static,
+{
     let tx = Arc::new(Mutex::new(Some(tx)));
 
-    let err_fn = |err| eprintln!("Stream error: {}", err);
+    let err_fn = |err| error!(error = %err, "stream error");
     let writer_2 = tx.clone();
-    let stream = d
-        .build_input_stream(
-            &cfg.into(),
-            move |data: &[T], _: &_| write_input_data::<T, T>(data, &writer_2),
-            err_fn,
-            None,
-        )
-        .expect("Error building stream");
+    let stream = d.build_input_stream(
+        &cfg,
+        move |data: &[T], _: &_| write_input_data::<T, U>(data, &writer_2, &stats),
+        err_fn,
+        None,
+    )?;
 
     stream.play()?;
 
@@ -106,39 +260,133 @@ fn capture_audio<
     Ok(())
 }
 
-fn capture_thread<T: cpal::SizedSample + hound::Sample + std::marker::Send + 'static>(
+fn capture_thread<U>(
     d: cpal::Device,
     cfg: cpal::StreamConfig,
-) -> mpsc::Receiver<T> {
-    let (tx, rx) = mpsc::channel::<T>(44100 * 2 * 300);
+    format: cpal::SampleFormat,
+    stats: Arc<Stats>,
+) -> mpsc::Receiver<U>
+where
+    U: cpal::Sample
+        + hound::Sample
+        + FromSample<i8>
+        + FromSample<i16>
+        + FromSample<i32>
+        + FromSample<i64>
+        + FromSample<u8>
+        + FromSample<u16>
+        + FromSample<u32>
+        + FromSample<u64>
+        + FromSample<f32>
+        + FromSample<f64>
+        + std::marker::Send
+        + 'static,
+{
+    let (tx, rx) = mpsc::channel::<U>(44100 * 2 * 300);
 
     std::thread::spawn(move || {
-        if let Err(e) = capture_audio(d, cfg, tx) {
-            eprintln!("Error capturing audio: {}", e);
+        let result = match format {
+            cpal::SampleFormat::I8 => capture_audio::<i8, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::I16 => capture_audio::<i16, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::I32 => capture_audio::<i32, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::I64 => capture_audio::<i64, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::U8 => capture_audio::<u8, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::U16 => capture_audio::<u16, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::U32 => capture_audio::<u32, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::U64 => capture_audio::<u64, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::F32 => capture_audio::<f32, U>(d, cfg, tx, stats),
+            cpal::SampleFormat::F64 => capture_audio::<f64, U>(d, cfg, tx, stats),
+            other => Err(format!("unsupported sample format {}", other).into()),
+        };
+        if let Err(e) = result {
+            error!(error = %e, "audio capture failed");
         }
     });
 
     rx
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let d = device().expect("Failed to get device");
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::parse();
+    let _log_guard = logging::init(&opt)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    if let Some(Command::Loadtest(args)) = &opt.command {
+        let uploader = Uploader::new(&opt, &opt.targets[0], RateLimit::new(&opt))?;
+        return runtime.block_on(loadtest::run(&uploader, args));
+    }
+
+    let controls = Controls::new();
+    // The window has to own the main thread, so recording moves off it.
+    #[cfg(feature = "gui")]
+    if opt.gui {
+        return gui::run(runtime, opt, controls);
+    }
+    runtime.block_on(record(opt, controls))
+}
+
+/// Records until the process is stopped, spooling chunks for the upload tasks.
+async fn record(opt: Opt, controls: Arc<Controls>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(checker) = UpdateChecker::new(&opt)? {
+        tokio::spawn(checker.run());
+    }
+    let archive = Archive::new(&opt)?;
+    let spool = Spool::new(&opt.spool_dir, &opt.targets)?;
+    // One budget for all targets: --max-upload-kbps is about the link.
+    let rate_limit = RateLimit::new(&opt);
+    let stats = Stats::new(&opt.targets);
+    for (index, (target, queue)) in opt.targets.iter().zip(spool.queues()).enumerate() {
+        let uploader = Uploader::new(&opt, target, rate_limit.clone())?;
+        tokio::spawn(
+            queue
+                .clone()
+                .drain(uploader, opt.upload_window, stats.clone(), index),
+        );
+    }
+    let off_the_record = OffTheRecord::new(opt.private);
+    private::hotkey_thread(off_the_record.clone());
+    let meeting = MeetingWatch::new(&opt.meeting_app)?;
+    if let Some(meeting) = &meeting {
+        session::watch_thread(meeting.clone());
+    }
+    let d = device(&opt).expect("Failed to get device");
+    stats.set_device(d.name().unwrap_or_default());
+    let mut addrs = vec![opt.status_addr, opt.metrics_addr];
+    addrs.dedup();
+    for addr in addrs.into_iter().flatten() {
+        status::serve(addr, stats.clone(), spool.queues().to_vec()).await?;
+    }
     let cfg = d
         .default_input_config()
         .expect("Failed to get default input config");
     let spec = wav_spec_from_config(&cfg);
 
-    println!("Default input config: {:?}", cfg);
+    info!(config = ?cfg, "using default input config");
 
     let strcfg: cpal::StreamConfig = cfg.clone().into();
 
-    match cfg.sample_format() {
-        cpal::SampleFormat::I8 => batch_and_send(capture_thread::<i8>(d, strcfg), spec).await?,
-        cpal::SampleFormat::I16 => batch_and_send(capture_thread::<i16>(d, strcfg), spec).await?,
-        cpal::SampleFormat::I32 => batch_and_send(capture_thread::<i32>(d, strcfg), spec).await?,
-        cpal::SampleFormat::F32 => batch_and_send(capture_thread::<f32>(d, strcfg), spec).await?,
-        _ => todo!(),
+    let format = cfg.sample_format();
+    if canonical_format(format) == cpal::SampleFormat::I16 {
+        batch_and_send(
+            capture_thread::<i16>(d, strcfg, format, stats.clone()),
+            spec,
+            &spool,
+            archive.as_ref(),
+            &off_the_record,
+            meeting.as_deref(),
+            &controls,
+        )
+        .await?
+    } else {
+        batch_and_send(
+            capture_thread::<f32>(d, strcfg, format, stats.clone()),
+            spec,
+            &spool,
+            archive.as_ref(),
+            &off_the_record,
+            meeting.as_deref(),
+            &controls,
+        )
+        .await?
     }
 
     Ok(())
@@ -149,24 +397,37 @@ async fn batch_and_send<
 >(
     mut rx: mpsc::Receiver<T>,
     spec: hound::WavSpec,
-) -> Result<(), Box<dyn std::error::Error>> {
+    spool: &Spool,
+    archive: Option<&Archive>,
+    off_the_record: &OffTheRecord,
+    meeting: Option<&MeetingWatch>,
+    controls: &Controls,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    f32: FromSample<T>,
+{
     let samples_per_second = 44100;
     let channels: usize = 2;
     let total_samples = (samples_per_second * channels * 30) as u32; // 5 minutes
     let total_samples_u = total_samples as usize;
     let mut buffer: Vec<T> = Vec::with_capacity(total_samples_u);
-    let start_time = Instant::now();
 
     while let Some(sample) = rx.recv().await {
+        if controls.paused() {
+            continue;
+        }
+        if controls.stopped() || meeting.is_some_and(|m| !m.active()) {
+            // Recording just stopped: close its last chunk early.
+            if !buffer.is_empty() {
+                finish_chunk(&buffer, spec, spool, archive, off_the_record, controls)?;
+                buffer.clear();
+            }
+            continue;
+        }
         buffer.push(sample);
 
         if buffer.len() >= total_samples_u {
-            let timestamp = start_time.elapsed().as_secs();
-            let filename = format!("audio_{}.wav", timestamp);
-            write_wav(&filename, &buffer, spec)?;
-
-            // Send WAV to server
-            send_wav(&filename).await?;
+            finish_chunk(&buffer, spec, spool, archive, off_the_record, controls)?;
 
             // Clear buffer
             buffer.clear();
@@ -175,6 +436,32 @@ async fn batch_and_send<
 
     Ok(())
 }
+
+fn finish_chunk<T: cpal::Sample + hound::Sample>(
+    buffer: &[T],
+    spec: hound::WavSpec,
+    spool: &Spool,
+    archive: Option<&Archive>,
+    off_the_record: &OffTheRecord,
+    controls: &Controls,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    f32: FromSample<T>,
+{
+    let private = off_the_record.take_chunk();
+    let chunk = spool.chunk_path(private, controls.system_key().as_deref());
+    write_wav(&chunk, buffer, spec)?;
+
+    if let Some(archive) = archive {
+        if let Err(e) = archive.store(&chunk, buffer, spec, private) {
+            warn!(chunk = %chunk.display(), error = %e, "failed to archive chunk");
+        }
+    }
+
+    // Hand the chunk over to the upload tasks
+    spool.push(&chunk)?;
+    Ok(())
+}
 fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
     if format.is_float() {
         hound::SampleFormat::Float
@@ -183,16 +470,17 @@ fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
     }
 }
 fn wav_spec_from_config(config: &cpal::SupportedStreamConfig) -> hound::WavSpec {
+    let format = canonical_format(config.sample_format());
     hound::WavSpec {
         channels: config.channels() as _,
         sample_rate: config.sample_rate().0 as _,
-        bits_per_sample: (config.sample_format().sample_size() * 8) as _,
-        sample_format: sample_format(config.sample_format()),
+        bits_per_sample: (format.sample_size() * 8) as _,
+        sample_format: sample_format(format),
     }
 }
 
 fn write_wav<T: hound::Sample + Clone>(
-    filename: &str,
+    filename: &Path,
     samples: &[T],
     spec: hound::WavSpec,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -205,45 +493,23 @@ fn write_wav<T: hound::Sample + Clone>(
     Ok(())
 }
 
-async fn send_wav(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let file = tokio::fs::read(filename).await?;
-    let part = reqwest::multipart::Part::bytes(file)
-        .file_name(filename.to_string())
-        .mime_str("audio/wav")?;
-
-    let form = reqwest::multipart::Form::new().part("file", part);
-
-    let response = client
-        .post("http://your-server-endpoint/upload")
-        .multipart(form)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        println!("Successfully sent {}", filename);
-    } else {
-        eprintln!("Failed to send {}: {}", filename, response.status());
-    }
-
-    // Optionally delete the file after sending
-    tokio::fs::remove_file(filename).await?;
-
-    Ok(())
-}
 type WavWriterHandle<T> = Arc<Mutex<Option<mpsc::Sender<T>>>>;
 
-fn write_input_data<T, U>(input: &[T], writer: &WavWriterHandle<U>)
+fn write_input_data<T, U>(input: &[T], writer: &WavWriterHandle<U>, stats: &Stats)
 where
     T: Sample,
     U: Sample + hound::Sample + FromSample<T>,
 {
+    let mut dropped = input.len() as u64;
     if let Ok(mut guard) = writer.try_lock() {
         if let Some(writer) = guard.as_mut() {
             for &sample in input.iter() {
                 let sample: U = U::from_sample(sample);
-                writer.try_send(sample).ok();
+                if writer.try_send(sample).is_ok() {
+                    dropped -= 1;
+                }
             }
         }
     }
+    stats.samples(input.len() as u64, dropped);
 }
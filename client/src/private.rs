@@ -0,0 +1,84 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::info;
+
+/// Off-the-record state, toggled from the terminal while recording.
+pub struct OffTheRecord {
+    active: AtomicBool,
+    // Whether the chunk being recorded overlapped an off-the-record span.
+    chunk: AtomicBool,
+}
+
+impl OffTheRecord {
+    pub fn new(active: bool) -> Arc<Self> {
+        Arc::new(OffTheRecord {
+            active: AtomicBool::new(active),
+            chunk: AtomicBool::new(active),
+        })
+    }
+
+    fn toggle(&self) -> bool {
+        let active = !self.active.fetch_xor(true, Ordering::SeqCst);
+        if active {
+            self.chunk.store(true, Ordering::SeqCst);
+        }
+        active
+    }
+
+    /// Closes the current chunk and reports whether any of it was recorded
+    /// off the record.
+    pub fn take_chunk(&self) -> bool {
+        self.chunk
+            .swap(self.active.load(Ordering::SeqCst), Ordering::SeqCst)
+    }
+}
+
+/// Toggles off-the-record mode whenever `p` followed by Enter is typed.
+pub fn hotkey_thread(state: Arc<OffTheRecord>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim() == "p" {
+                if state.toggle() {
+                    info!("off the record: chunks are flagged private");
+                } else {
+                    info!("back on the record");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_follow_the_mode() {
+        let state = OffTheRecord::new(false);
+        assert!(!state.take_chunk());
+
+        assert!(state.toggle());
+        assert!(state.take_chunk());
+        assert!(state.take_chunk(), "still off the record");
+    }
+
+    #[test]
+    fn chunk_overlapping_a_private_span_is_private() {
+        let state = OffTheRecord::new(false);
+        state.toggle();
+        state.toggle();
+        assert!(state.take_chunk(), "went private during the chunk");
+        assert!(!state.take_chunk());
+    }
+
+    #[test]
+    fn starting_private() {
+        let state = OffTheRecord::new(true);
+        assert!(state.take_chunk());
+        assert!(!state.toggle());
+        assert!(state.take_chunk(), "chunk began off the record");
+        assert!(!state.take_chunk());
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Manual recording controls, driven from the GUI. Stopping closes the
+/// current chunk; pausing keeps it open and resumes into it.
+pub struct Controls {
+    stopped: AtomicBool,
+    paused: AtomicBool,
+    // Recorded with each chunk finished from now on, and used for targets
+    // without a system key of their own.
+    system_key: Mutex<Option<String>>,
+    // Why recording ended, once it has.
+    #[cfg(feature = "gui")]
+    failed: Mutex<Option<String>>,
+}
+
+impl Controls {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Controls {
+            stopped: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            system_key: Mutex::new(None),
+            #[cfg(feature = "gui")]
+            failed: Mutex::new(None),
+        })
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn system_key(&self) -> Option<String> {
+        self.system_key.lock().unwrap().clone()
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn set_stopped(&self, stopped: bool) {
+        self.stopped.store(stopped, Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn set_system_key(&self, key: Option<String>) {
+        *self.system_key.lock().unwrap() = key;
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn failed(&self) -> Option<String> {
+        self.failed.lock().unwrap().clone()
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn set_failed(&self, reason: String) {
+        *self.failed.lock().unwrap() = Some(reason);
+    }
+}
+
+/// Tracks whether one of the configured conferencing apps is holding a
+/// microphone, so that recording follows meetings without any input.
+pub struct MeetingWatch {
+    apps: Vec<String>,
+    active: AtomicBool,
+}
+
+impl MeetingWatch {
+    pub fn new(apps: &[String]) -> Result<Option<Arc<Self>>, Box<dyn std::error::Error>> {
+        if apps.is_empty() {
+            return Ok(None);
+        }
+        // Fail at startup rather than silently never recording.
+        mic_users()?;
+        Ok(Some(Arc::new(MeetingWatch {
+            apps: apps.iter().map(|a| a.to_lowercase()).collect(),
+            active: AtomicBool::new(false),
+        })))
+    }
+
+    /// Whether a meeting is in progress and audio should be recorded.
+    pub fn active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn meeting_app(&self, users: &[String]) -> Option<String> {
+        users
+            .iter()
+            .map(|u| u.to_lowercase())
+            .find(|u| self.apps.iter().any(|a| u.contains(a.as_str())))
+    }
+}
+
+/// Polls the system for microphone users and flips the watch on when a
+/// configured app opens the mic and off when it lets go.
+pub fn watch_thread(watch: Arc<MeetingWatch>) {
+    std::thread::spawn(move || loop {
+        match mic_users() {
+            Ok(users) => {
+                let app = watch.meeting_app(&users);
+                let was_active = watch.active.swap(app.is_some(), Ordering::SeqCst);
+                match (was_active, app) {
+                    (false, Some(app)) => info!(%app, "meeting started: recording"),
+                    (true, None) => info!("meeting ended: finalizing session"),
+                    _ => {}
+                }
+            }
+            Err(e) => warn!(error = %e, "failed to list microphone users"),
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    });
+}
+
+/// Applications currently recording from a source, as reported by the
+/// PulseAudio or PipeWire server.
+#[cfg(target_os = "linux")]
+fn mic_users() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = std::process::Command::new("pactl")
+        .args(["list", "source-outputs"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("pactl exited with {}", output.status).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once(" = ")?;
+            matches!(key, "application.name" | "application.process.binary")
+                .then(|| value.trim_matches('"').to_string())
+        })
+        .collect())
+}
+
+/// Applications currently using the microphone, from the privacy consent
+/// store: an entry whose LastUsedTimeStop is zero has not released it yet.
+#[cfg(target_os = "windows")]
+fn mic_users() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = std::process::Command::new("reg")
+        .args([
+            "query",
+            r"HKCU\Software\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore\microphone",
+            "/s",
+            "/v",
+            "LastUsedTimeStop",
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("reg query exited with {}", output.status).into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut users = Vec::new();
+    let mut key = "";
+    for line in stdout.lines() {
+        if line.starts_with("HKEY_") {
+            key = line.rsplit('\\').next().unwrap_or_default();
+        } else if line.trim_start().starts_with("LastUsedTimeStop")
+            && line.trim_end().ends_with("0x0")
+        {
+            users.push(key.replace('#', "\\"));
+        }
+    }
+    Ok(users)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn mic_users() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Err("--meeting-app is only supported on Linux and Windows".into())
+}
@@ -0,0 +1,307 @@
+use chrono::NaiveTime;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{error, warn};
+
+use crate::status::Stats;
+use crate::upload::{self, Target, Uploader};
+
+const PRIVATE_SUFFIX: &str = "_private";
+const KEY_PREFIX: &str = "_key-";
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Daily time range, in local time, during which uploads may run. The end may
+/// be earlier than the start for windows that span midnight.
+#[derive(Clone, Copy, Debug)]
+pub struct UploadWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl UploadWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for UploadWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected HH:MM-HH:MM, got {}", s))?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|e| format!("{}: {}", t, e))
+        };
+        Ok(UploadWindow {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// Directory of recorded chunks waiting to be uploaded. Recording only ever
+/// writes here; each target drains its own queue, so a slow, failing or
+/// deferred target never holds up capture or the other targets.
+pub struct Spool {
+    dir: PathBuf,
+    queues: Vec<Queue>,
+}
+
+/// One target's share of the spool, with its own retry state.
+#[derive(Clone)]
+pub struct Queue {
+    dir: PathBuf,
+    notify: Arc<Notify>,
+}
+
+impl Spool {
+    pub fn new(dir: &Path, targets: &[Target]) -> Result<Self, Box<dyn std::error::Error>> {
+        // Two drain tasks on one queue would each take some of its chunks.
+        for (i, target) in targets.iter().enumerate() {
+            if targets[..i]
+                .iter()
+                .any(|t| t.queue_name() == target.queue_name())
+            {
+                return Err(format!("target {} is given more than once", target).into());
+            }
+        }
+
+        let mut queues = Vec::with_capacity(targets.len());
+        for target in targets {
+            let dir = dir.join(target.queue_name());
+            std::fs::create_dir_all(&dir)?;
+            queues.push(Queue {
+                dir,
+                notify: Arc::new(Notify::new()),
+            });
+        }
+        Ok(Spool {
+            dir: dir.to_path_buf(),
+            queues,
+        })
+    }
+
+    pub fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    /// Path to write the next chunk to before handing it to `push`. The
+    /// private flag and the system key picked while recording travel in the
+    /// file name so that they survive a restart.
+    pub fn chunk_path(&self, private: bool, system_key: Option<&str>) -> PathBuf {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        self.dir
+            .join(chunk_stem(millis, private, system_key))
+            .with_extension("part")
+    }
+
+    /// Queues a finished chunk for every target and wakes their upload tasks.
+    pub fn push(&self, chunk: &Path) -> std::io::Result<()> {
+        let name = Path::new(chunk.file_name().unwrap_or_default()).with_extension("wav");
+        for queue in &self.queues {
+            let queued = queue.dir.join(&name);
+            if std::fs::hard_link(chunk, &queued).is_err() {
+                std::fs::copy(chunk, &queued)?;
+            }
+            queue.notify.notify_one();
+        }
+        std::fs::remove_file(chunk)
+    }
+}
+
+impl Queue {
+    /// Chunks waiting for upload, oldest first.
+    pub fn pending(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut chunks = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "wav") {
+                chunks.push(path);
+            }
+        }
+        chunks.sort();
+        Ok(chunks)
+    }
+
+    /// Uploads queued chunks one by one, oldest first, for as long as the
+    /// process runs. Outside the upload window chunks are left to accumulate.
+    pub async fn drain(
+        self,
+        uploader: Uploader,
+        window: Option<UploadWindow>,
+        stats: Arc<Stats>,
+        index: usize,
+    ) {
+        let mut delay = Duration::from_secs(5);
+        loop {
+            if let Some(window) = window {
+                if !window.contains(chrono::Local::now().time()) {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    continue;
+                }
+            }
+
+            let next = match self.pending() {
+                Ok(chunks) => chunks.into_iter().next(),
+                Err(e) => {
+                    error!(spool = %self.dir.display(), error = %e, "failed to read spool");
+                    None
+                }
+            };
+            let Some(path) = next else {
+                // Also wake up periodically in case a chunk was missed.
+                let _ = tokio::time::timeout(Duration::from_secs(30), self.notify.notified()).await;
+                continue;
+            };
+
+            let (private, system_key) = chunk_tags(&path);
+            // The error is not Send, so it is reported before sleeping.
+            match uploader
+                .send_wav(&path, private, system_key.as_deref())
+                .await
+            {
+                Ok(status) if !upload::retryable(status) => {
+                    if status.is_success() {
+                        stats.upload_succeeded(index);
+                    } else {
+                        stats.upload_failed(index);
+                    }
+                    delay = Duration::from_secs(5);
+                    continue;
+                }
+                Ok(status) => {
+                    stats.upload_failed(index);
+                    warn!(
+                        chunk = %path.display(),
+                        retry_in = ?delay,
+                        status = %status,
+                        "server unavailable"
+                    );
+                }
+                Err(e) => {
+                    stats.upload_failed(index);
+                    warn!(
+                    chunk = %path.display(),
+                    retry_in = ?delay,
+                    error = %e,
+                    "upload failed"
+                    );
+                }
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
+
+/// File name, without extension, of a chunk finished at `millis`. The key is
+/// hex-encoded so that any key makes a valid file name.
+fn chunk_stem(millis: u128, private: bool, system_key: Option<&str>) -> String {
+    let mut stem = format!("audio_{}", millis);
+    if let Some(key) = system_key {
+        stem.push_str(KEY_PREFIX);
+        stem.push_str(&hex::encode(key));
+    }
+    if private {
+        stem.push_str(PRIVATE_SUFFIX);
+    }
+    stem
+}
+
+/// Reads back whether a chunk is private and which system key it was
+/// recorded under, as written by `chunk_stem`.
+fn chunk_tags(path: &Path) -> (bool, Option<String>) {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let (stem, private) = match stem.strip_suffix(PRIVATE_SUFFIX) {
+        Some(stem) => (stem, true),
+        None => (stem, false),
+    };
+    let system_key = stem
+        .split_once(KEY_PREFIX)
+        .and_then(|(_, key)| hex::decode(key).ok())
+        .and_then(|key| String::from_utf8(key).ok());
+    (private, system_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window: UploadWindow = "09:00-17:30".parse().unwrap();
+        assert!(window.contains(at("09:00")));
+        assert!(window.contains(at("12:00")));
+        assert!(!window.contains(at("17:30")));
+        assert!(!window.contains(at("08:59")));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let window: UploadWindow = "18:00-08:00".parse().unwrap();
+        assert!(window.contains(at("18:00")));
+        assert!(window.contains(at("23:59")));
+        assert!(window.contains(at("00:00")));
+        assert!(window.contains(at("07:59")));
+        assert!(!window.contains(at("08:00")));
+        assert!(!window.contains(at("12:00")));
+    }
+
+    #[test]
+    fn window_tolerates_spaces() {
+        let window: UploadWindow = "22:00 - 06:00".parse().unwrap();
+        assert!(window.contains(at("23:00")));
+    }
+
+    #[test]
+    fn duplicate_targets_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("spool-dup-{}", std::process::id()));
+        let targets: Vec<Target> = ["http://a.com/x", "http://a.com/x/"]
+            .iter()
+            .map(|t| t.parse().unwrap())
+            .collect();
+        assert!(Spool::new(&dir, &targets).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn chunk_names_keep_their_tags() {
+        for private in [false, true] {
+            for key in [None, Some("room-1"), Some("Board room / 3.OG")] {
+                let name = format!("{}.wav", chunk_stem(1_700_000_000_000, private, key));
+                assert_eq!(
+                    chunk_tags(Path::new(&name)),
+                    (private, key.map(String::from))
+                );
+            }
+        }
+        assert_eq!(chunk_tags(Path::new("audio_17.wav")), (false, None));
+        assert_eq!(chunk_tags(Path::new("audio_17_key-zz.wav")), (false, None));
+    }
+
+    #[test]
+    fn window_rejects_malformed_input() {
+        assert!("18:00".parse::<UploadWindow>().is_err());
+        assert!("18:00-25:00".parse::<UploadWindow>().is_err());
+        assert!("six-eight".parse::<UploadWindow>().is_err());
+    }
+}
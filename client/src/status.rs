@@ -0,0 +1,298 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::spool::Queue;
+use crate::upload::Target;
+
+/// Counters shared between the capture thread, the upload tasks and the
+/// status and metrics endpoints.
+pub struct Stats {
+    started: Instant,
+    device: Mutex<String>,
+    samples_captured: AtomicU64,
+    samples_dropped: AtomicU64,
+    targets: Vec<TargetStats>,
+}
+
+struct TargetStats {
+    target: String,
+    last_success: Mutex<Option<DateTime<Local>>>,
+    chunks_uploaded: AtomicU64,
+    upload_failures: AtomicU64,
+}
+
+impl Stats {
+    pub fn new(targets: &[Target]) -> Arc<Self> {
+        Arc::new(Stats {
+            started: Instant::now(),
+            device: Mutex::new(String::new()),
+            samples_captured: AtomicU64::new(0),
+            samples_dropped: AtomicU64::new(0),
+            targets: targets
+                .iter()
+                .map(|t| TargetStats {
+                    target: t.to_string(),
+                    last_success: Mutex::new(None),
+                    chunks_uploaded: AtomicU64::new(0),
+                    upload_failures: AtomicU64::new(0),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn set_device(&self, name: String) {
+        *self.device.lock().unwrap() = name;
+    }
+
+    /// Records samples delivered by the device, of which `dropped` never made
+    /// it into a chunk.
+    pub fn samples(&self, captured: u64, dropped: u64) {
+        self.samples_captured.fetch_add(captured, Ordering::Relaxed);
+        if dropped > 0 {
+            self.samples_dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a successful upload to the target at `index`.
+    pub fn upload_succeeded(&self, index: usize) {
+        if let Some(target) = self.targets.get(index) {
+            target.chunks_uploaded.fetch_add(1, Ordering::Relaxed);
+            *target.last_success.lock().unwrap() = Some(Local::now());
+        }
+    }
+
+    /// Records a failed or rejected upload to the target at `index`.
+    pub fn upload_failed(&self, index: usize) {
+        if let Some(target) = self.targets.get(index) {
+            target.upload_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    device: String,
+    uptime_secs: u64,
+    samples_dropped: u64,
+    spool_chunks: usize,
+    targets: Vec<TargetStatus>,
+}
+
+#[derive(Serialize)]
+struct TargetStatus {
+    target: String,
+    pending_chunks: usize,
+    last_success: Option<String>,
+}
+
+fn status(stats: &Stats, queues: &[Queue]) -> Status {
+    let targets: Vec<TargetStatus> = stats
+        .targets
+        .iter()
+        .zip(queues)
+        .map(|(target, queue)| TargetStatus {
+            target: target.target.clone(),
+            pending_chunks: queue.pending().map(|p| p.len()).unwrap_or_default(),
+            last_success: target.last_success.lock().unwrap().map(|t| t.to_rfc3339()),
+        })
+        .collect();
+
+    Status {
+        device: stats.device.lock().unwrap().clone(),
+        uptime_secs: stats.started.elapsed().as_secs(),
+        samples_dropped: stats.samples_dropped.load(Ordering::Relaxed),
+        spool_chunks: targets.iter().map(|t| t.pending_chunks).sum(),
+        targets,
+    }
+}
+
+/// Renders the counters in the Prometheus text exposition format.
+fn metrics(stats: &Stats, queues: &[Queue]) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} {}\n",
+            name, help, name, kind
+        ));
+        for (labels, value) in samples {
+            out.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    };
+    let per_target = |value: &dyn Fn(usize) -> u64| {
+        stats
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                (
+                    format!("{{target=\"{}\"}}", escape_label(&t.target)),
+                    value(i),
+                )
+            })
+            .collect()
+    };
+
+    metric(
+        "client_samples_captured_total",
+        "counter",
+        "Samples delivered by the capture device.",
+        vec![(
+            String::new(),
+            stats.samples_captured.load(Ordering::Relaxed),
+        )],
+    );
+    metric(
+        "client_samples_dropped_total",
+        "counter",
+        "Captured samples that never made it into a chunk.",
+        vec![(String::new(), stats.samples_dropped.load(Ordering::Relaxed))],
+    );
+    metric(
+        "client_chunks_uploaded_total",
+        "counter",
+        "Chunks accepted by the target.",
+        per_target(&|i| stats.targets[i].chunks_uploaded.load(Ordering::Relaxed)),
+    );
+    metric(
+        "client_upload_failures_total",
+        "counter",
+        "Uploads that failed or were rejected by the target.",
+        per_target(&|i| stats.targets[i].upload_failures.load(Ordering::Relaxed)),
+    );
+    metric(
+        "client_spool_backlog_chunks",
+        "gauge",
+        "Chunks waiting in the spool for the target.",
+        per_target(&|i| {
+            queues
+                .get(i)
+                .and_then(|q| q.pending().ok())
+                .map_or(0, |p| p.len() as u64)
+        }),
+    );
+    metric(
+        "client_uptime_seconds",
+        "gauge",
+        "Seconds since the client started.",
+        vec![(String::new(), stats.started.elapsed().as_secs())],
+    );
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /status` as JSON and `GET /metrics` for Prometheus on `addr`,
+/// so headless recorders can be checked on without reading their logs.
+pub async fn serve(
+    addr: SocketAddr,
+    stats: Arc<Stats>,
+    queues: Vec<Queue>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("status endpoint listening on http://{}", addr);
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // Errors like EMFILE persist; don't spin on them.
+                    warn!(error = %e, "status endpoint failed to accept");
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let stats = stats.clone();
+            let queues = queues.clone();
+            tokio::spawn(async move {
+                if let Err(e) = respond(stream, &stats, &queues).await {
+                    warn!(error = %e, "status request failed");
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn respond(stream: TcpStream, stats: &Stats, queues: &[Queue]) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    // Drain the headers; the request body, if any, is ignored.
+    let mut header = String::new();
+    while stream.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status_line, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/status")) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&status(stats, queues)).map_err(std::io::Error::other)?,
+        ),
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics(stats, queues),
+        ),
+        _ => (
+            "404 Not Found",
+            "application/json",
+            String::from("{\"error\":\"not found\"}"),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    stream.get_mut().shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("http://a.com#room"), "http://a.com#room");
+        assert_eq!(escape_label("a\"b"), "a\\\"b");
+        assert_eq!(escape_label("a\\b"), "a\\\\b");
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+        assert_eq!(escape_label("\\\""), "\\\\\\\"");
+    }
+
+    #[test]
+    fn metrics_carry_escaped_target_labels() {
+        let target: Target = "http://a.com#say \"hi\"".parse().unwrap();
+        let stats = Stats::new(&[target]);
+        stats.upload_succeeded(0);
+        stats.upload_failed(0);
+        stats.samples(10, 3);
+
+        let text = metrics(&stats, &[]);
+        assert!(text.contains("client_samples_captured_total 10\n"));
+        assert!(text.contains("client_samples_dropped_total 3\n"));
+        assert!(text
+            .contains("client_chunks_uploaded_total{target=\"http://a.com#say \\\"hi\\\"\"} 1\n"));
+        assert!(text.contains("# TYPE client_spool_backlog_chunks gauge\n"));
+    }
+}
@@ -0,0 +1,203 @@
+use clap::ValueEnum;
+use ed25519_dalek::{Signature, VerifyingKey};
+use semver::Version;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::Opt;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum UpdateMode {
+    /// Only log that a newer version is available
+    Notify,
+    /// Download, verify and replace the running binary
+    Install,
+}
+
+/// What the update URL is expected to return.
+#[derive(Deserialize)]
+struct Manifest {
+    version: String,
+    /// Where to download the new binary from
+    url: String,
+    /// Hex-encoded Ed25519 signature over `version`, a newline and the binary,
+    /// so a signed build cannot be passed off under another version
+    signature: String,
+}
+
+pub struct UpdateChecker {
+    client: reqwest::Client,
+    url: String,
+    mode: UpdateMode,
+    key: Option<VerifyingKey>,
+    interval: Duration,
+    // Version on disk; ahead of the running one once an update is installed.
+    installed: Version,
+}
+
+impl UpdateChecker {
+    pub fn new(opt: &Opt) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        remove_replaced_exe();
+        let Some(url) = &opt.update_url else {
+            return Ok(None);
+        };
+        let key = match &opt.update_public_key {
+            Some(hex_key) => {
+                let mut bytes = [0u8; 32];
+                hex::decode_to_slice(hex_key.trim(), &mut bytes)
+                    .map_err(|e| format!("--update-public-key: {}", e))?;
+                Some(VerifyingKey::from_bytes(&bytes)?)
+            }
+            None => None,
+        };
+        if opt.update_mode == UpdateMode::Install && key.is_none() {
+            return Err("--update-mode install needs --update-public-key".into());
+        }
+
+        Ok(Some(UpdateChecker {
+            client: reqwest::Client::new(),
+            url: url.clone(),
+            mode: opt.update_mode,
+            key,
+            interval: Duration::from_secs(opt.update_interval_hours.max(1) * 3600),
+            installed: Version::parse(env!("CARGO_PKG_VERSION"))?,
+        }))
+    }
+
+    /// Checks for updates now and then every interval, for as long as the
+    /// process runs.
+    pub async fn run(mut self) {
+        loop {
+            if let Err(e) = self.check().await {
+                warn!(url = %self.url, error = %e, "update check failed");
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    async fn check(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let manifest: Manifest = serde_json::from_slice(&body)?;
+        let current = self.installed.clone();
+        let latest = Version::parse(&manifest.version)?;
+        if latest <= current {
+            return Ok(());
+        }
+
+        match (self.mode, &self.key) {
+            (UpdateMode::Install, Some(key)) => {
+                self.install(&manifest, key).await?;
+                self.installed = latest.clone();
+                info!(%current, %latest, "update installed; restart the client to use it");
+            }
+            _ => info!(%current, %latest, url = %manifest.url, "a newer client is available"),
+        }
+        Ok(())
+    }
+
+    /// Downloads the new binary, verifies its signature and swaps it in for
+    /// the running executable. Nothing is replaced unless verification passes.
+    async fn install(
+        &self,
+        manifest: &Manifest,
+        key: &VerifyingKey,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let binary = self
+            .client
+            .get(&manifest.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        verify(key, &manifest.version, &binary, &manifest.signature)?;
+
+        let exe = std::env::current_exe()?;
+        let staged = exe.with_extension("update");
+        tokio::fs::write(&staged, &binary).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755)).await?;
+        }
+        // Windows will not replace a running executable, but it does let one
+        // be renamed out of the way. The old copy is removed on next start.
+        #[cfg(windows)]
+        tokio::fs::rename(&exe, exe.with_extension("exe.old")).await?;
+        tokio::fs::rename(&staged, &exe).await?;
+        Ok(())
+    }
+}
+
+/// Checks `signature` (hex) against the version line followed by the binary.
+fn verify(
+    key: &VerifyingKey,
+    version: &str,
+    binary: &[u8],
+    signature: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut bytes = [0u8; 64];
+    hex::decode_to_slice(signature.trim(), &mut bytes)?;
+    let mut signed = format!("{}\n", version).into_bytes();
+    signed.extend_from_slice(binary);
+    key.verify_strict(&signed, &Signature::from_bytes(&bytes))
+        .map_err(|_| "update signature does not match the configured public key")?;
+    Ok(())
+}
+
+/// Deletes the executable an update on Windows renamed out of the way.
+fn remove_replaced_exe() {
+    if !cfg!(windows) {
+        return;
+    }
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let old = exe.with_extension("exe.old");
+    if old.exists() {
+        if let Err(e) = std::fs::remove_file(&old) {
+            warn!(path = %old.display(), error = %e, "failed to remove replaced executable");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn sign(key: &SigningKey, version: &str, binary: &[u8]) -> String {
+        let mut signed = format!("{}\n", version).into_bytes();
+        signed.extend_from_slice(binary);
+        hex::encode(key.sign(&signed).to_bytes())
+    }
+
+    #[test]
+    fn signature_over_version_and_binary_is_accepted() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let signature = sign(&key, "1.2.0", b"new client");
+        assert!(verify(&key.verifying_key(), "1.2.0", b"new client", &signature).is_ok());
+    }
+
+    #[test]
+    fn tampered_updates_are_rejected() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let public = key.verifying_key();
+        let signature = sign(&key, "1.2.0", b"new client");
+
+        assert!(verify(&public, "9.9.9", b"new client", &signature).is_err());
+        assert!(verify(&public, "1.2.0", b"new clienT", &signature).is_err());
+        assert!(verify(&public, "1.2.0", b"new client", "00").is_err());
+
+        let other = SigningKey::from_bytes(&[8u8; 32]).verifying_key();
+        assert!(verify(&other, "1.2.0", b"new client", &signature).is_err());
+    }
+}
@@ -0,0 +1,815 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use clap::ValueEnum;
+use reqwest::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::Opt;
+
+const TUS_VERSION: &str = "1.0.0";
+const RESUMABLE_CHUNK_SIZE: usize = 1024 * 1024;
+const RESUMABLE_MAX_ATTEMPTS: u32 = 5;
+const ENCRYPTION_SCHEME: &str = "xchacha20poly1305";
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn content_encoding(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(data, 0)?),
+        }
+    }
+}
+
+/// A server to upload to, optionally with the system key chunks are filed
+/// under. Written as `URL` or `URL#SYSTEM_KEY`.
+#[derive(Clone, Debug)]
+pub struct Target {
+    url: String,
+    system_key: Option<String>,
+}
+
+impl Target {
+    #[cfg(feature = "gui")]
+    pub fn system_key(&self) -> Option<&str> {
+        self.system_key.as_deref()
+    }
+
+    /// Where the server publishes the latest summary for `key`.
+    #[cfg(feature = "gui")]
+    pub fn summary_url(&self, key: &str) -> String {
+        format!("{}/summary/{}", self.url, key)
+    }
+
+    /// Directory name for this target's spool queue. Hashing the whole target
+    /// keeps targets that only differ in punctuation apart.
+    pub fn queue_name(&self) -> String {
+        let digest = Sha256::digest(self.to_string());
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (url, system_key) = match s.split_once('#') {
+            Some((url, key)) if !key.is_empty() => (url, Some(key.to_string())),
+            Some((url, _)) => (url, None),
+            None => (s, None),
+        };
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("{}: expected an http(s) URL", url));
+        }
+        Ok(Target {
+            url: url.trim_end_matches('/').to_string(),
+            system_key,
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.system_key {
+            Some(key) => write!(f, "{}#{}", self.url, key),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+/// Shared state for sending recorded chunks to one target.
+#[derive(Clone)]
+pub struct Uploader {
+    client: reqwest::Client,
+    target: Target,
+    compress: Option<Compression>,
+    resumable_threshold: u64,
+    cipher: Option<XChaCha20Poly1305>,
+    rate_limit: Option<RateLimit>,
+    client_id: String,
+}
+
+impl Uploader {
+    pub fn new(
+        opt: &Opt,
+        target: &Target,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let cipher = match &opt.encryption_key {
+            Some(path) => Some(XChaCha20Poly1305::new(&read_key(path)?.into())),
+            None => None,
+        };
+
+        Ok(Uploader {
+            client: reqwest::Client::new(),
+            target: target.clone(),
+            compress: opt.compress,
+            resumable_threshold: opt.resumable_threshold,
+            cipher,
+            rate_limit,
+            client_id: client_id(opt),
+        })
+    }
+
+    /// Uploads a spooled chunk. It is deleted once delivered, and moved to
+    /// `rejected/` beside it if the server refuses it for good; a chunk the
+    /// server may still take is left in place for the caller to retry.
+    /// `system_key` is the key picked while the chunk was recorded; it is
+    /// only sent to a target without a key of its own.
+    pub async fn send_wav(
+        &self,
+        path: &Path,
+        private: bool,
+        system_key: Option<&str>,
+    ) -> Result<StatusCode, Box<dyn std::error::Error>> {
+        let file = tokio::fs::read(path).await?;
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Progress of a resumable upload survives retries and restarts here.
+        let resume = path.with_extension("tus");
+        let status = self
+            .upload_chunk(&filename, file, private, system_key, Some(&resume))
+            .await?;
+
+        if status.is_success() {
+            info!(chunk = %filename, target = %self.target, "chunk uploaded");
+            tokio::fs::remove_file(path).await?;
+        } else if retryable(status) {
+            return Ok(status);
+        } else {
+            // Kept for a look by hand rather than deleted: the server may
+            // be wrong, and the recording cannot be made again.
+            let rejected = path.with_file_name("rejected");
+            tokio::fs::create_dir_all(&rejected).await?;
+            tokio::fs::rename(path, rejected.join(&filename)).await?;
+            warn!(
+                chunk = %filename,
+                target = %self.target,
+                status = %status,
+                moved_to = %rejected.display(),
+                "server rejected chunk"
+            );
+        }
+        if resume.exists() {
+            tokio::fs::remove_file(&resume).await?;
+        }
+
+        Ok(status)
+    }
+
+    /// Uploads one WAV payload and returns the status the server answered with.
+    pub async fn upload(
+        &self,
+        filename: &str,
+        file: Vec<u8>,
+        private: bool,
+    ) -> Result<StatusCode, Box<dyn std::error::Error>> {
+        self.upload_chunk(filename, file, private, None, None).await
+    }
+
+    /// Like `upload`, but keeps resumable upload progress in `resume` so that
+    /// a later call for the same chunk picks up where this one stopped.
+    async fn upload_chunk(
+        &self,
+        filename: &str,
+        mut file: Vec<u8>,
+        private: bool,
+        system_key: Option<&str>,
+        resume: Option<&Path>,
+    ) -> Result<StatusCode, Box<dyn std::error::Error>> {
+        let mut state = resume.map(ResumeState::load).unwrap_or_default();
+        let mut mime = "audio/wav";
+        let mut fields = Vec::new();
+        if let Some(key) = self.target.system_key.as_deref().or(system_key) {
+            fields.push(("system_key", key.to_string()));
+        }
+        fields.push(("client_id", self.client_id.clone()));
+        if let Some(cipher) = &self.cipher {
+            // A resumed upload has to send the same bytes as before.
+            let nonce = match state.nonce() {
+                Some(nonce) => nonce,
+                None => XChaCha20Poly1305::generate_nonce(&mut OsRng),
+            };
+            state.nonce = Some(BASE64.encode(nonce));
+            file = encrypt(cipher, &nonce, &file)?;
+            mime = "application/octet-stream";
+            fields.push(("encryption", ENCRYPTION_SCHEME.to_string()));
+        }
+        if private {
+            // Tells the server to keep the chunk away from the LLM.
+            fields.push(("private", "true".to_string()));
+        }
+        // Lets the server reject uploads truncated on the way.
+        fields.push(("sha256", format!("{:x}", Sha256::digest(&file))));
+
+        if file.len() as u64 > self.resumable_threshold {
+            // tus has no Content-Encoding, so the payload itself is compressed
+            // and the encoding is passed along in the metadata.
+            let mut encoding = None;
+            if let Some(compression) = self.compress {
+                file = compression.encode(&file)?;
+                encoding = Some(compression.content_encoding());
+            }
+            return self
+                .upload_resumable(filename, &file, &fields, encoding, resume, state)
+                .await;
+        }
+
+        let request = self.client.post(format!("{}/upload", self.target.url));
+        let request = match self.compress {
+            Some(compression) => {
+                // reqwest only streams multipart forms, so the body is encoded by
+                // hand to be able to compress it as a whole.
+                let boundary = multipart_boundary();
+                let body = compression
+                    .encode(&multipart_body(&boundary, filename, mime, &file, &fields))?;
+                request
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .header(
+                        reqwest::header::CONTENT_ENCODING,
+                        compression.content_encoding(),
+                    )
+                    .body(self.body(body))
+            }
+            None => {
+                let len = file.len() as u64;
+                let part = reqwest::multipart::Part::stream_with_length(self.body(file), len)
+                    .file_name(filename.to_string())
+                    .mime_str(mime)?;
+
+                let form = fields.into_iter().fold(
+                    reqwest::multipart::Form::new().part("file", part),
+                    |form, (name, value)| form.text(name, value),
+                );
+                request.multipart(form)
+            }
+        };
+
+        Ok(request.send().await?.status())
+    }
+
+    fn body(&self, data: Vec<u8>) -> reqwest::Body {
+        match &self.rate_limit {
+            Some(limit) => limit.body(data),
+            None => data.into(),
+        }
+    }
+
+    /// Uploads `data` with the tus 1.0 protocol: create the upload, then PATCH it
+    /// in chunks, asking the server for its offset again whenever a chunk fails.
+    /// An upload recorded in `state` is continued rather than created anew.
+    async fn upload_resumable(
+        &self,
+        filename: &str,
+        data: &[u8],
+        fields: &[(&str, String)],
+        content_encoding: Option<&str>,
+        resume: Option<&Path>,
+        mut state: ResumeState,
+    ) -> Result<StatusCode, Box<dyn std::error::Error>> {
+        let client = &self.client;
+
+        let mut resumed = None;
+        if let Some(url) = state.upload_url(data, content_encoding) {
+            let head = self.head(&url).await?;
+            if head.status().is_success() {
+                if let Some(offset) = upload_offset(head.headers().get("Upload-Offset")) {
+                    info!(chunk = %filename, offset, "resuming upload");
+                    resumed = Some((url, offset, head.status()));
+                }
+            } else if retryable(head.status()) {
+                return Ok(head.status());
+            }
+            // Otherwise the server forgot the upload; start a new one.
+        }
+
+        let (upload_url, mut offset, mut status) = match resumed {
+            Some(resumed) => resumed,
+            None => {
+                let metadata = std::iter::once(("filename", filename))
+                    .chain(fields.iter().map(|(name, value)| (*name, value.as_str())))
+                    .chain(content_encoding.map(|encoding| ("content_encoding", encoding)))
+                    .map(|(name, value)| format!("{} {}", name, BASE64.encode(value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let created = client
+                    .post(format!("{}/files", self.target.url))
+                    .header("Tus-Resumable", TUS_VERSION)
+                    .header("Upload-Length", data.len())
+                    .header("Upload-Metadata", metadata)
+                    .send()
+                    .await?;
+                if !created.status().is_success() {
+                    return Ok(created.status());
+                }
+                let location = created
+                    .headers()
+                    .get(LOCATION)
+                    .ok_or("resumable upload created without a Location header")?
+                    .to_str()?;
+                let upload_url = created.url().join(location)?;
+                if let Some(resume) = resume {
+                    state.start(&upload_url, data, content_encoding);
+                    state.save(resume)?;
+                }
+                (upload_url, 0, created.status())
+            }
+        };
+
+        let mut attempts = 0;
+        while offset < data.len() {
+            let end = (offset + RESUMABLE_CHUNK_SIZE).min(data.len());
+            let sent = client
+                .patch(upload_url.clone())
+                .header("Tus-Resumable", TUS_VERSION)
+                .header("Upload-Offset", offset)
+                .header(CONTENT_TYPE, "application/offset+octet-stream")
+                .body(self.body(data[offset..end].to_vec()))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+
+            let failure = match sent {
+                Ok(response) => {
+                    status = response.status();
+                    let next =
+                        upload_offset(response.headers().get("Upload-Offset")).unwrap_or(end);
+                    if next > offset {
+                        offset = next;
+                        attempts = 0;
+                        continue;
+                    }
+                    // Accepted without moving on; sending the same piece again
+                    // would go on forever.
+                    format!("server stayed at offset {}", next)
+                }
+                Err(e) => e.to_string(),
+            };
+            warn!(
+                chunk = %filename,
+                offset,
+                error = %failure,
+                "resumable upload interrupted"
+            );
+            // Ask the server how much arrived. While it stays out of reach
+            // every probe uses up an attempt too.
+            offset = loop {
+                attempts += 1;
+                if attempts >= RESUMABLE_MAX_ATTEMPTS {
+                    return Err(failure.into());
+                }
+                tokio::time::sleep(Duration::from_secs(1 << attempts)).await;
+
+                match self
+                    .head(&upload_url)
+                    .await
+                    .and_then(|r| r.error_for_status())
+                {
+                    Ok(head) => {
+                        break upload_offset(head.headers().get("Upload-Offset"))
+                            .ok_or("server did not report an Upload-Offset")?
+                    }
+                    Err(e) => warn!(chunk = %filename, error = %e, "upload offset unknown"),
+                }
+            };
+        }
+
+        Ok(status)
+    }
+
+    async fn head(&self, upload_url: &Url) -> reqwest::Result<reqwest::Response> {
+        self.client
+            .head(upload_url.clone())
+            .header("Tus-Resumable", TUS_VERSION)
+            .send()
+            .await
+    }
+}
+
+/// What a resumable upload needs to be continued later, stored as JSON next
+/// to the chunk.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct ResumeState {
+    upload_url: Option<String>,
+    /// Upload-Length the upload was created with
+    upload_length: Option<usize>,
+    content_encoding: Option<String>,
+    /// Digest of the bytes being uploaded, which change with any option that
+    /// shapes the payload
+    sha256: Option<String>,
+    /// Base64 nonce the chunk was encrypted with, if it was
+    nonce: Option<String>,
+}
+
+impl ResumeState {
+    fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Records a newly created upload of `data`.
+    fn start(&mut self, upload_url: &Url, data: &[u8], content_encoding: Option<&str>) {
+        self.upload_url = Some(upload_url.to_string());
+        self.upload_length = Some(data.len());
+        self.content_encoding = content_encoding.map(String::from);
+        self.sha256 = Some(format!("{:x}", Sha256::digest(data)));
+    }
+
+    /// The upload to continue, unless it was started for a different payload;
+    /// appending to that one would corrupt it.
+    fn upload_url(&self, data: &[u8], content_encoding: Option<&str>) -> Option<Url> {
+        if self.upload_length != Some(data.len())
+            || self.content_encoding.as_deref() != content_encoding
+            || self.sha256 != Some(format!("{:x}", Sha256::digest(data)))
+        {
+            return None;
+        }
+        Url::parse(self.upload_url.as_deref()?).ok()
+    }
+
+    fn nonce(&self) -> Option<XNonce> {
+        let nonce = BASE64.decode(self.nonce.as_deref()?).ok()?;
+        XNonce::from_exact_iter(nonce)
+    }
+}
+
+/// Upload bandwidth shared by every uploader it is given to, so that all
+/// targets together stay within `--max-upload-kbps`.
+#[derive(Clone)]
+pub struct RateLimit {
+    bytes_per_sec: f64,
+    /// When everything handed out so far has gone out at the limit
+    next: Arc<Mutex<Instant>>,
+}
+
+impl RateLimit {
+    pub fn new(opt: &Opt) -> Option<Self> {
+        let kbps = opt.max_upload_kbps?;
+        Some(RateLimit {
+            bytes_per_sec: (kbps * 1000 / 8).max(1) as f64,
+            next: Arc::new(Mutex::new(Instant::now())),
+        })
+    }
+
+    /// Waits until `len` more bytes fit in the budget.
+    async fn take(&self, len: usize) {
+        let due = {
+            let mut next = self.next.lock().unwrap();
+            let due = (*next).max(Instant::now());
+            *next = due + Duration::from_secs_f64(len as f64 / self.bytes_per_sec);
+            due
+        };
+        tokio::time::sleep_until(due).await;
+    }
+
+    /// Streams `data` in small pieces, each one held back until it fits in
+    /// the budget.
+    fn body(&self, data: Vec<u8>) -> reqwest::Body {
+        const PIECE: usize = 16 * 1024;
+
+        let limit = self.clone();
+        let stream = futures_util::stream::unfold((data, 0), move |(data, sent)| {
+            let limit = limit.clone();
+            async move {
+                if sent >= data.len() {
+                    return None;
+                }
+                let end = (sent + PIECE).min(data.len());
+                limit.take(end - sent).await;
+                let piece = data[sent..end].to_vec();
+                Some((Ok::<_, std::io::Error>(piece), (data, end)))
+            }
+        });
+        reqwest::Body::wrap_stream(stream)
+    }
+}
+
+/// `--client-id`, else the host name. A recorder that cannot read its host
+/// name still runs, under an id generated once per process.
+fn client_id(opt: &Opt) -> String {
+    static GENERATED: OnceLock<String> = OnceLock::new();
+
+    if let Some(id) = &opt.client_id {
+        return id.clone();
+    }
+    match whoami::fallible::hostname() {
+        Ok(hostname) => hostname,
+        Err(e) => GENERATED
+            .get_or_init(|| {
+                let id = format!("client-{:08x}", OsRng.next_u32());
+                warn!(error = %e, client_id = %id, "could not read the host name; set --client-id");
+                id
+            })
+            .clone(),
+    }
+}
+
+/// Whether the server may still accept the chunk later. Besides overload,
+/// restarts and requests to slow down, that covers 401, 403 and 404: a key
+/// being rotated or a server still being deployed, not a broken chunk.
+pub fn retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || matches!(
+            status,
+            StatusCode::UNAUTHORIZED
+                | StatusCode::FORBIDDEN
+                | StatusCode::NOT_FOUND
+                | StatusCode::REQUEST_TIMEOUT
+                | StatusCode::TOO_MANY_REQUESTS
+        )
+}
+
+fn upload_offset(value: Option<&HeaderValue>) -> Option<usize> {
+    value?.to_str().ok()?.parse().ok()
+}
+
+fn multipart_boundary() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("------------------------{:032x}", nanos)
+}
+
+fn multipart_body(
+    boundary: &str,
+    filename: &str,
+    mime: &str,
+    data: &[u8],
+    fields: &[(&str, String)],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(data.len() + 512);
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary, filename, mime
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(data);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// Reads a 256-bit key stored as 64 hex characters.
+fn read_key(path: &Path) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    let hex = std::fs::read_to_string(path)?;
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("{}: expected 64 hex characters", path.display()).into());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(key)
+}
+
+/// Seals `data` as `nonce || ciphertext`, so the server only needs the shared
+/// key to open it.
+fn encrypt(
+    cipher: &XChaCha20Poly1305,
+    nonce: &XNonce,
+    data: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let ciphertext = cipher
+        .encrypt(nonce, data)
+        .map_err(|_| "failed to encrypt audio")?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_body_layout() {
+        let fields = [("sha256", String::from("abc"))];
+        let body = multipart_body("XYZ", "a.wav", "audio/wav", b"RIFF", &fields);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--XYZ\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\
+             Content-Type: audio/wav\r\n\r\n\
+             RIFF\r\n\
+             --XYZ\r\n\
+             Content-Disposition: form-data; name=\"sha256\"\r\n\r\n\
+             abc\r\n\
+             --XYZ--\r\n"
+        );
+    }
+
+    #[test]
+    fn multipart_body_keeps_binary_data() {
+        let data = [0u8, 0xff, b'\r', b'\n', 0x80];
+        let body = multipart_body("B", "a.wav", "application/octet-stream", &data, &[]);
+        assert!(body.windows(data.len()).any(|w| w == data));
+        assert!(body.ends_with(b"\r\n--B--\r\n"));
+    }
+
+    fn target(s: &str) -> Target {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn target_with_and_without_key() {
+        let t = target("https://example.com/api/#room-1");
+        assert_eq!(t.url, "https://example.com/api");
+        assert_eq!(t.system_key.as_deref(), Some("room-1"));
+
+        let t = target("http://example.com");
+        assert_eq!(t.url, "http://example.com");
+        assert_eq!(t.system_key, None);
+
+        assert_eq!(target("http://example.com#").system_key, None);
+    }
+
+    #[test]
+    fn target_display_round_trips() {
+        for s in ["http://example.com#room-1", "https://example.com/api"] {
+            assert_eq!(target(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn target_needs_http() {
+        assert!("ftp://example.com".parse::<Target>().is_err());
+        assert!("example.com#room".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn queue_names_keep_targets_apart() {
+        let a = target("http://a.com/x").queue_name();
+        let b = target("http://a.com#x").queue_name();
+        assert_ne!(a, b);
+        assert_eq!(a, target("http://a.com/x/").queue_name());
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_is_shared_between_clones() {
+        let limit = RateLimit {
+            bytes_per_sec: 10_000.0,
+            next: Arc::new(Mutex::new(Instant::now())),
+        };
+        let other = limit.clone();
+        let started = Instant::now();
+        limit.take(1000).await;
+        other.take(1000).await;
+        other.take(1000).await;
+        assert!(started.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        for code in [401, 403, 404, 408, 429, 500, 502, 503] {
+            assert!(retryable(StatusCode::from_u16(code).unwrap()), "{}", code);
+        }
+        for code in [200, 201, 400, 413, 415, 422] {
+            assert!(!retryable(StatusCode::from_u16(code).unwrap()), "{}", code);
+        }
+    }
+
+    #[test]
+    fn upload_offset_parses_header() {
+        assert_eq!(
+            upload_offset(Some(&HeaderValue::from_static("1024"))),
+            Some(1024)
+        );
+        assert_eq!(upload_offset(Some(&HeaderValue::from_static("-1"))), None);
+        assert_eq!(upload_offset(Some(&HeaderValue::from_static("abc"))), None);
+        assert_eq!(upload_offset(None), None);
+    }
+
+    #[test]
+    fn resume_state_round_trips() {
+        let path = std::env::temp_dir().join(format!("resume-{}.tus", std::process::id()));
+        assert_eq!(ResumeState::load(&path), ResumeState::default());
+
+        let url = Url::parse("http://example.com/files/1").unwrap();
+        let mut state = ResumeState::default();
+        state.start(&url, b"payload", Some("zstd"));
+        state.nonce = Some(BASE64.encode([9u8; 24]));
+        state.save(&path).unwrap();
+        let loaded = ResumeState::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.upload_url(b"payload", Some("zstd")), Some(url));
+        assert_eq!(loaded.nonce(), Some(XNonce::from([9u8; 24])));
+    }
+
+    #[test]
+    fn resume_state_rejects_other_payloads() {
+        let url = Url::parse("http://example.com/files/1").unwrap();
+        let mut state = ResumeState::default();
+        state.start(&url, b"payload", Some("zstd"));
+        assert_eq!(state.upload_url(b"payload", None), None);
+        assert_eq!(state.upload_url(b"payload", Some("gzip")), None);
+        assert_eq!(state.upload_url(b"payloaD", Some("zstd")), None);
+        assert_eq!(state.upload_url(b"longer payload", Some("zstd")), None);
+        assert_eq!(ResumeState::default().upload_url(b"", None), None);
+    }
+
+    #[test]
+    fn encrypted_audio_opens_with_the_shared_key() {
+        let cipher = XChaCha20Poly1305::new(&[7u8; 32].into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = encrypt(&cipher, &nonce, b"RIFF audio").unwrap();
+
+        let (sent_nonce, ciphertext) = sealed.split_at(24);
+        assert_eq!(sent_nonce, nonce.as_slice());
+        let opened = cipher
+            .decrypt(XNonce::from_slice(sent_nonce), ciphertext)
+            .unwrap();
+        assert_eq!(opened, b"RIFF audio");
+
+        let other = XChaCha20Poly1305::new(&[8u8; 32].into());
+        assert!(other
+            .decrypt(XNonce::from_slice(sent_nonce), ciphertext)
+            .is_err());
+    }
+
+    #[test]
+    fn read_key_needs_64_hex_characters() {
+        let path = std::env::temp_dir().join(format!("key-{}.hex", std::process::id()));
+        let read = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            read_key(&path)
+        };
+
+        let key = read(&format!("{}\n", "0f".repeat(32))).unwrap();
+        assert_eq!(key, [0x0f; 32]);
+        assert!(read("0f0f").is_err());
+        assert!(read(&"0f".repeat(33)).is_err());
+        assert!(read(&format!("zz{}", "0f".repeat(31))).is_err());
+        assert!(read(&format!("+f{}", "0f".repeat(31))).is_err());
+        assert!(read(&format!("é{}", "0".repeat(62))).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_key(&path).is_err());
+    }
+
+    #[test]
+    fn compressed_bodies_decode() {
+        let data = vec![7u8; 4096];
+        let gzip = Compression::Gzip.encode(&data).unwrap();
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&gzip[..]), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+        let zstd = Compression::Zstd.encode(&data).unwrap();
+        assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), data);
+    }
+}